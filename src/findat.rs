@@ -0,0 +1,102 @@
+use anyhow::Error;
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::checkout;
+use crate::git::{Git2Repository, GitRepository};
+use crate::GEResult;
+
+const DATETIME_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"];
+
+fn parse_when(when: &str) -> GEResult<i64> {
+    if let Ok(timestamp) = when.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    for format in DATETIME_FORMATS {
+        if let Ok(parsed) = NaiveDateTime::parse_from_str(when, format) {
+            return Ok(parsed.and_utc().timestamp());
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(when, "%Y-%m-%d") {
+        return Ok(date
+            .and_hms_opt(0, 0, 0)
+            .ok_or_else(|| Error::msg("midnight is not a valid time on this date"))?
+            .and_utc()
+            .timestamp());
+    }
+    Err(Error::msg(format!(
+        "could not parse '{}' as a date, datetime, or unix timestamp",
+        when
+    )))
+}
+
+/// Locates the commit that was the tip of the current branch at `when`:
+/// the newest commit in first-parent history whose committer date is at
+/// or before the target.
+///
+/// First-parent history is newest-first and *usually* decreasing in
+/// committer date, but rebases can make that order locally
+/// non-monotonic, so a binary search over the raw sequence isn't sound
+/// (it can skip straight past the true boundary commit). Scan linearly
+/// instead — first-parent history is bounded by mainline length, so
+/// this stays cheap in practice while always returning the right answer.
+pub fn find_at(when: &str, do_checkout: bool, verbose: bool) -> GEResult<()> {
+    let target = parse_when(when)?;
+    let repo = Git2Repository::discover()?;
+    let history = repo.first_parent_history()?;
+    let oldest = history
+        .last()
+        .ok_or_else(|| Error::msg("repository has no commits"))?;
+    if target < oldest.1 {
+        return Err(Error::msg(format!(
+            "{} predates the repository's first commit ({})",
+            when, oldest.0
+        )));
+    }
+
+    let pivot = history
+        .iter()
+        .position(|(_, ts)| *ts <= target)
+        .ok_or_else(|| Error::msg("no commit found at or before the given time"))?;
+
+    let (hash, _) = &history[pivot];
+    println!("{}", hash);
+    if do_checkout {
+        checkout(hash, verbose)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_when;
+
+    #[test]
+    fn parses_raw_unix_timestamp() {
+        assert_eq!(parse_when("1700000000").unwrap(), 1700000000);
+    }
+
+    #[test]
+    fn parses_space_separated_datetime() {
+        assert_eq!(
+            parse_when("2023-01-02 03:04:05").unwrap(),
+            parse_when("2023-01-02T03:04:05").unwrap()
+        );
+    }
+
+    #[test]
+    fn parses_t_separated_datetime() {
+        assert_eq!(parse_when("2023-01-02T03:04:05").unwrap(), 1672628645);
+    }
+
+    #[test]
+    fn parses_bare_date_as_midnight_utc() {
+        assert_eq!(parse_when("2023-01-02").unwrap(), 1672617600);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(parse_when("not-a-date").is_err());
+        assert!(parse_when("2023-13-40").is_err());
+        assert!(parse_when("").is_err());
+    }
+}
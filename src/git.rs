@@ -0,0 +1,487 @@
+use std::process::Command;
+
+use anyhow::Error;
+use colored::*;
+use git2::{BranchType, ResetType};
+
+use crate::GEResult;
+
+/// Ahead/behind counts for a branch relative to its upstream.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Status {
+    pub ahead: Option<i32>,
+    pub behind: Option<i32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BranchDescriptor {
+    pub current: bool,
+    pub name: String,
+    pub sha: String,
+    pub upstream: Option<String>,
+    pub message: String,
+    pub status: Option<Status>,
+    /// Committer date of the branch tip, as a Unix timestamp.
+    pub timestamp: i64,
+}
+
+/// A source of git operations.
+///
+/// `Git2Repository` is the primary implementation, talking to `.git`
+/// directly via `git2` instead of spawning a `git` subprocess and
+/// scraping its output. `ShellRepository` exists only as a fallback for
+/// operations libgit2 doesn't handle cleanly (submodule recursion,
+/// chiefly) and for commands this crate hasn't been ported yet.
+pub trait GitRepository {
+    fn current_branch(&self) -> GEResult<String>;
+    fn upstream_of(&self, branch: &str) -> GEResult<Option<String>>;
+    fn last_hash(&self) -> GEResult<String>;
+    fn is_clean(&self) -> GEResult<bool>;
+    fn branches(&self) -> GEResult<Vec<BranchDescriptor>>;
+    fn checkout(&self, branch: &str) -> GEResult<()>;
+    fn reset_hard(&self, target: &str) -> GEResult<()>;
+    fn cherry_pick(&self, commit: &str) -> GEResult<()>;
+    fn set_upstream(&self, branch: &str, upstream: &str) -> GEResult<()>;
+    fn update_submodules(&self) -> GEResult<()>;
+    /// The commit at the tip of HEAD and each of its first parents in
+    /// turn, newest first, as `(hash, committer_unix_timestamp)` pairs.
+    fn first_parent_history(&self) -> GEResult<Vec<(String, i64)>>;
+    fn has_conflicts(&self) -> GEResult<bool>;
+    /// The commit id of the cherry-pick currently in progress, i.e.
+    /// `CHERRY_PICK_HEAD`.
+    fn cherry_pick_head(&self) -> GEResult<String>;
+    /// Finishes a cherry-pick left mid-flight by a conflict, using the
+    /// message of the commit recorded in `CHERRY_PICK_HEAD`.
+    fn finish_cherry_pick(&self) -> GEResult<()>;
+    /// Aborts a cherry-pick left mid-flight by a conflict, discarding
+    /// any staged resolution.
+    fn abort_cherry_pick(&self) -> GEResult<()>;
+}
+
+/// The result of parsing `git status --porcelain=v2 --branch` output.
+pub struct PorcelainStatus {
+    pub status: Status,
+    pub clean: bool,
+}
+
+/// Parses `git status --porcelain=v2 --branch` output into a typed
+/// `Status` and cleanliness flag, instead of scraping the locale-
+/// dependent prose of plain `git status` or the `[ahead N, behind M]`
+/// fragment of `git branch -vv`.
+///
+/// The `# branch.ab +A -B` header line gives ahead/behind directly; the
+/// tree is clean iff there are no `1` (changed), `2` (renamed/copied),
+/// `u` (unmerged), or `?` (untracked) entry lines.
+pub fn parse_porcelain_status(output: &str) -> PorcelainStatus {
+    let mut ahead = None;
+    let mut behind = None;
+    let mut clean = true;
+
+    for line in output.lines() {
+        if let Some(rest) = line.strip_prefix("# branch.ab ") {
+            let mut counts = rest.split_whitespace();
+            ahead = counts
+                .next()
+                .and_then(|a| a.strip_prefix('+'))
+                .and_then(|a| a.parse::<i32>().ok())
+                .filter(|a| *a > 0);
+            behind = counts
+                .next()
+                .and_then(|b| b.strip_prefix('-'))
+                .and_then(|b| b.parse::<i32>().ok())
+                .filter(|b| *b > 0);
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with(['1', '2', 'u', '?']) {
+            clean = false;
+        }
+    }
+
+    PorcelainStatus {
+        status: Status { ahead, behind },
+        clean,
+    }
+}
+
+/// The parsed porcelain-v2 status of the branch currently checked out,
+/// including its ahead/behind counts relative to its upstream (if any).
+/// This is the only view of ahead/behind that `git status` itself can
+/// give, so it's used for the current branch's row in `ShowTree`
+/// instead of (or alongside) `Git2Repository::branches`.
+pub fn current_status() -> GEResult<PorcelainStatus> {
+    let output = run_git(vec!["status", "--porcelain=v2", "--branch"], false)?;
+    Ok(parse_porcelain_status(&output))
+}
+
+pub fn run_git(cmdargs: Vec<&str>, verbose: bool) -> GEResult<String> {
+    let cmd_string = format!("{} {}", "git".bright_white().on_green(), cmdargs.join(" "));
+
+    if verbose {
+        println!("{}", cmd_string);
+    }
+    let output = Command::new("git").args(cmdargs).output()?;
+    if !output.status.success() {
+        println!("{}", String::from_utf8(output.stderr)?);
+        return Err(Error::msg(format!(
+            "git exited with status {}",
+            output.status.code().unwrap_or(-1)
+        )));
+    }
+    let output = String::from_utf8(output.stdout)?;
+    let trimmed = output.trim();
+    if verbose {
+        println!("{}", trimmed)
+    }
+
+    Ok(String::from(trimmed))
+}
+
+/// Talks to the repository at the current directory directly via
+/// `git2`, falling back to shelling out for the handful of operations
+/// (submodule recursion, porcelain-v2 working-tree status, aborting a
+/// cherry-pick) that libgit2 doesn't handle cleanly.
+pub struct Git2Repository {
+    repo: git2::Repository,
+}
+
+impl Git2Repository {
+    pub fn discover() -> GEResult<Self> {
+        Ok(Git2Repository {
+            repo: git2::Repository::discover(".")?,
+        })
+    }
+}
+
+impl GitRepository for Git2Repository {
+    fn current_branch(&self) -> GEResult<String> {
+        let head = self.repo.head()?;
+        Ok(head
+            .shorthand()
+            .ok_or_else(|| Error::msg("HEAD does not point at a valid utf-8 branch name"))?
+            .to_string())
+    }
+
+    fn upstream_of(&self, branch: &str) -> GEResult<Option<String>> {
+        let local = self.repo.find_branch(branch, BranchType::Local)?;
+        match local.upstream() {
+            Ok(up) => Ok(up.name()?.map(String::from)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn last_hash(&self) -> GEResult<String> {
+        Ok(self.repo.head()?.peel_to_commit()?.id().to_string())
+    }
+
+    fn is_clean(&self) -> GEResult<bool> {
+        Ok(current_status()?.clean)
+    }
+
+    fn branches(&self) -> GEResult<Vec<BranchDescriptor>> {
+        let head_name = self.current_branch().ok();
+        let mut out = vec![];
+        for item in self.repo.branches(Some(BranchType::Local))? {
+            let (branch, _) = item?;
+            let name = branch
+                .name()?
+                .ok_or_else(|| Error::msg("branch name is not valid utf-8"))?
+                .to_string();
+            let commit = branch.get().peel_to_commit()?;
+            let sha = commit.id().to_string();
+            let message = commit.summary().unwrap_or("").to_string();
+            let timestamp = commit.time().seconds();
+            let upstream = branch
+                .upstream()
+                .ok()
+                .and_then(|up| up.name().ok().flatten().map(String::from));
+            let status = upstream.as_ref().and_then(|up_name| {
+                let up_oid = self
+                    .repo
+                    .find_branch(up_name, BranchType::Remote)
+                    .ok()
+                    .and_then(|up| up.get().target())?;
+                let (ahead, behind) = self
+                    .repo
+                    .graph_ahead_behind(commit.id(), up_oid)
+                    .unwrap_or((0, 0));
+                Some(Status {
+                    ahead: if ahead > 0 { Some(ahead as i32) } else { None },
+                    behind: if behind > 0 { Some(behind as i32) } else { None },
+                })
+            });
+            out.push(BranchDescriptor {
+                current: head_name.as_deref() == Some(name.as_str()),
+                name,
+                sha,
+                upstream,
+                message,
+                status,
+                timestamp,
+            });
+        }
+        Ok(out)
+    }
+
+    fn checkout(&self, branch: &str) -> GEResult<()> {
+        let (object, reference) = self.repo.revparse_ext(branch)?;
+        self.repo.checkout_tree(&object, None)?;
+        match reference {
+            Some(gref) => self.repo.set_head(
+                gref.name()
+                    .ok_or_else(|| Error::msg("branch name is not valid utf-8"))?,
+            )?,
+            None => self.repo.set_head_detached(object.id())?,
+        }
+        Ok(())
+    }
+
+    fn reset_hard(&self, target: &str) -> GEResult<()> {
+        let (object, _) = self.repo.revparse_ext(target)?;
+        self.repo.reset(&object, ResetType::Hard, None)?;
+        Ok(())
+    }
+
+    fn cherry_pick(&self, commit: &str) -> GEResult<()> {
+        let oid = git2::Oid::from_str(commit)?;
+        let commit = self.repo.find_commit(oid)?;
+        self.repo.cherrypick(&commit, None)?;
+        if self.repo.index()?.has_conflicts() {
+            return Err(Error::msg(
+                "cherry-pick produced conflicts; resolve and stage them, then run `git_ext continue`"
+                    .white()
+                    .on_bright_red()
+                    .to_string(),
+            ));
+        }
+        let tree_id = self.repo.index()?.write_tree()?;
+        let tree = self.repo.find_tree(tree_id)?;
+        let head_commit = self.repo.head()?.peel_to_commit()?;
+        let committer = self.repo.signature()?;
+        self.repo.commit(
+            Some("HEAD"),
+            &commit.author(),
+            &committer,
+            commit.message().unwrap_or(""),
+            &tree,
+            &[&head_commit],
+        )?;
+        self.repo.cleanup_state()?;
+        Ok(())
+    }
+
+    fn set_upstream(&self, branch: &str, upstream: &str) -> GEResult<()> {
+        let mut local = self.repo.find_branch(branch, BranchType::Local)?;
+        local.set_upstream(Some(upstream))?;
+        Ok(())
+    }
+
+    fn update_submodules(&self) -> GEResult<()> {
+        ShellRepository.update_submodules()
+    }
+
+    fn first_parent_history(&self) -> GEResult<Vec<(String, i64)>> {
+        let mut out = vec![];
+        let mut commit = self.repo.head()?.peel_to_commit()?;
+        loop {
+            out.push((commit.id().to_string(), commit.time().seconds()));
+            commit = match commit.parent(0) {
+                Ok(parent) => parent,
+                Err(_) => break,
+            };
+        }
+        Ok(out)
+    }
+
+    fn has_conflicts(&self) -> GEResult<bool> {
+        Ok(self.repo.index()?.has_conflicts())
+    }
+
+    fn cherry_pick_head(&self) -> GEResult<String> {
+        Ok(self
+            .repo
+            .find_reference("CHERRY_PICK_HEAD")?
+            .peel_to_commit()?
+            .id()
+            .to_string())
+    }
+
+    fn finish_cherry_pick(&self) -> GEResult<()> {
+        let cherry_pick_head = self
+            .repo
+            .find_reference("CHERRY_PICK_HEAD")?
+            .peel_to_commit()?;
+        if self.repo.index()?.has_conflicts() {
+            return Err(Error::msg("there are still unresolved conflicts"));
+        }
+        let tree_id = self.repo.index()?.write_tree()?;
+        let tree = self.repo.find_tree(tree_id)?;
+        let head_commit = self.repo.head()?.peel_to_commit()?;
+        let committer = self.repo.signature()?;
+        self.repo.commit(
+            Some("HEAD"),
+            &cherry_pick_head.author(),
+            &committer,
+            cherry_pick_head.message().unwrap_or(""),
+            &tree,
+            &[&head_commit],
+        )?;
+        self.repo.cleanup_state()?;
+        Ok(())
+    }
+
+    fn abort_cherry_pick(&self) -> GEResult<()> {
+        run_git(vec!["cherry-pick", "--abort"], true)?;
+        Ok(())
+    }
+}
+
+/// Falls back to spawning `git` for operations libgit2 can't do
+/// cleanly, chiefly recursive submodule updates.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShellRepository;
+
+impl GitRepository for ShellRepository {
+    fn current_branch(&self) -> GEResult<String> {
+        run_git(vec!["rev-parse", "--abbrev-ref", "HEAD"], false)
+    }
+
+    fn upstream_of(&self, branch: &str) -> GEResult<Option<String>> {
+        let refspec = format!("{}@{{u}}", branch);
+        match run_git(
+            vec!["rev-parse", "--abbrev-ref", "--symbolic-full-name", &refspec],
+            false,
+        ) {
+            Ok(upstream) => Ok(Some(upstream)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn last_hash(&self) -> GEResult<String> {
+        run_git(vec!["log", "-n", "1", "--pretty=format:%H"], false)
+    }
+
+    fn is_clean(&self) -> GEResult<bool> {
+        Ok(current_status()?.clean)
+    }
+
+    fn branches(&self) -> GEResult<Vec<BranchDescriptor>> {
+        Err(Error::msg(
+            "listing branches is not supported by the shell fallback; use Git2Repository",
+        ))
+    }
+
+    fn checkout(&self, branch: &str) -> GEResult<()> {
+        run_git(vec!["checkout", branch], true)?;
+        Ok(())
+    }
+
+    fn reset_hard(&self, target: &str) -> GEResult<()> {
+        run_git(vec!["reset", "--hard", target, "--"], true)?;
+        Ok(())
+    }
+
+    fn cherry_pick(&self, commit: &str) -> GEResult<()> {
+        run_git(vec!["cherry-pick", commit], true)?;
+        Ok(())
+    }
+
+    fn set_upstream(&self, branch: &str, upstream: &str) -> GEResult<()> {
+        run_git(vec!["branch", "--set-upstream-to", upstream, branch], true)?;
+        Ok(())
+    }
+
+    fn update_submodules(&self) -> GEResult<()> {
+        run_git(vec!["submodule", "init"], true)?;
+        run_git(vec!["submodule", "update", "--recursive"], true)?;
+        Ok(())
+    }
+
+    fn first_parent_history(&self) -> GEResult<Vec<(String, i64)>> {
+        run_git(
+            vec!["log", "--first-parent", "--pretty=format:%H %ct"],
+            false,
+        )?
+        .lines()
+        .map(|line| {
+            let (hash, ts) = line
+                .split_once(' ')
+                .ok_or_else(|| Error::msg(format!("unexpected `git log` line: {line}")))?;
+            Ok((hash.to_string(), ts.parse::<i64>()?))
+        })
+        .collect()
+    }
+
+    fn has_conflicts(&self) -> GEResult<bool> {
+        let output = run_git(vec!["status", "--porcelain=v2", "--branch"], false)?;
+        Ok(output.lines().any(|line| line.starts_with('u')))
+    }
+
+    fn cherry_pick_head(&self) -> GEResult<String> {
+        run_git(vec!["rev-parse", "CHERRY_PICK_HEAD"], false)
+    }
+
+    fn finish_cherry_pick(&self) -> GEResult<()> {
+        run_git(vec!["cherry-pick", "--continue"], true)?;
+        Ok(())
+    }
+
+    fn abort_cherry_pick(&self) -> GEResult<()> {
+        run_git(vec!["cherry-pick", "--abort"], true)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_porcelain_status;
+
+    #[test]
+    fn clean_tree_with_ahead_behind() {
+        let status = parse_porcelain_status(
+            "# branch.oid abc123\n# branch.head main\n# branch.upstream origin/main\n# branch.ab +2 -1\n",
+        );
+        assert!(status.clean);
+        assert_eq!(status.status.ahead, Some(2));
+        assert_eq!(status.status.behind, Some(1));
+    }
+
+    #[test]
+    fn zero_ahead_behind_is_none() {
+        let status = parse_porcelain_status("# branch.ab +0 -0\n");
+        assert_eq!(status.status.ahead, None);
+        assert_eq!(status.status.behind, None);
+    }
+
+    #[test]
+    fn changed_entry_marks_dirty() {
+        let status = parse_porcelain_status(
+            "# branch.ab +0 -0\n1 .M N... 100644 100644 100644 deadbeef deadbeef src/main.rs\n",
+        );
+        assert!(!status.clean);
+    }
+
+    #[test]
+    fn untracked_entry_marks_dirty() {
+        let status = parse_porcelain_status("# branch.ab +0 -0\n? new_file.rs\n");
+        assert!(!status.clean);
+    }
+
+    #[test]
+    fn unmerged_entry_marks_dirty() {
+        let status = parse_porcelain_status(
+            "# branch.ab +0 -0\nu UU N... 100644 100644 100644 100644 aaa bbb ccc src/main.rs\n",
+        );
+        assert!(!status.clean);
+    }
+
+    #[test]
+    fn missing_branch_ab_header_defaults_to_clean() {
+        let status = parse_porcelain_status("");
+        assert!(status.clean);
+        assert_eq!(status.status.ahead, None);
+        assert_eq!(status.status.behind, None);
+    }
+}
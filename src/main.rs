@@ -1,385 +1,128 @@
-use std::collections::HashMap;
-use std::iter::Iterator;
-use std::process::Command;
+mod findat;
+mod git;
+mod restack;
+mod resume;
+mod state;
+mod tree;
 
 use anyhow::Error;
 use clap::{Parser, Subcommand};
-use colored::*;
-use comfy_table::{presets, Cell, CellAlignment, Table};
 use dialoguer::Confirm;
 use regex::Regex;
 
-type GEResult<T> = Result<T, Error>;
+use git::{run_git, Git2Repository, GitRepository};
 
-fn run_git(cmdargs: Vec<&str>, verbose: bool) -> GEResult<String> {
-    let cmd_string = format!("{} {}", "git".bright_white().on_green(), cmdargs.join(" "));
-
-    if verbose {
-        println!("{}", cmd_string);
-    }
-    let output = Command::new("git").args(cmdargs).output()?;
-    if !output.status.success() {
-        println!("{}", String::from_utf8(output.stderr)?);
-        return Err(Error::msg(format!(
-            "git exited with status {}",
-            output.status.code().unwrap_or(-1)
-        )));
-    }
-    let output = String::from_utf8(output.stdout)?;
-    let trimmed = output.trim();
-    if verbose {
-        println!("{}", trimmed)
-    }
-
-    Ok(String::from(trimmed))
-}
-
-fn lasthash(verbose: bool) -> GEResult<String> {
-    run_git(vec!["log", "-n", "1", "--pretty=format:%H"], verbose)
-}
+pub type GEResult<T> = Result<T, Error>;
 
 fn ensure_clean() -> GEResult<()> {
-    let status = run_git(vec!["status"], false)?;
-    if !(status.contains("nothing to commit, working directory clean")
-        || status.contains("nothing to commit, working tree clean"))
-    {
-        return Err(Error::msg(status.white().on_bright_red()));
+    if !Git2Repository::discover()?.is_clean()? {
+        return Err(Error::msg(
+            "working tree is not clean (uncommitted changes present)",
+        ));
     }
     Ok(())
 }
 
 fn handle_submodules(verbose: bool) -> GEResult<()> {
-    run_git(vec!["submodule", "init"], verbose)?;
-    run_git(vec!["submodule", "update", "--recursive"], verbose)?;
-    Ok(())
+    let _ = verbose;
+    Git2Repository::discover()?.update_submodules()
 }
 
-fn get_upstream(verbose: bool) -> GEResult<String> {
-    run_git(
-        vec!["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{u}"],
-        verbose,
-    )
+fn get_upstream() -> GEResult<String> {
+    let repo = Git2Repository::discover()?;
+    let branch = repo.current_branch()?;
+    repo.upstream_of(&branch)?
+        .ok_or_else(|| Error::msg(format!("branch '{branch}' has no upstream configured")))
 }
 
-fn get_curr_branch(verbose: bool) -> GEResult<String> {
-    run_git(vec!["rev-parse", "--abbrev-ref", "HEAD"], verbose)
+fn get_curr_branch() -> GEResult<String> {
+    Git2Repository::discover()?.current_branch()
 }
 
 fn fix_upstream(upstream: &str, verbose: bool) -> GEResult<()> {
-    let commit = lasthash(verbose)?;
-    run_git(vec!["branch", "--set-upstream-to", upstream], true)?;
+    let repo = Git2Repository::discover()?;
+    let commit = repo.last_hash()?;
+    let branch = repo.current_branch()?;
+    repo.set_upstream(&branch, upstream)?;
     ensure_clean()?;
-    run_git(vec!["reset", "--hard", upstream, "--"], true)?;
-    handle_submodules(true)?;
-    run_git(vec!["cherry-pick", commit.as_str()], true)?;
-    handle_submodules(true)?;
+    repo.reset_hard(upstream)?;
+    handle_submodules(verbose)?;
+    repo.cherry_pick(&commit)?;
+    handle_submodules(verbose)?;
     Ok(())
 }
 
 fn checkout(branch: &str, verbose: bool) -> GEResult<()> {
-    run_git(vec!["checkout", branch], verbose)?;
+    Git2Repository::discover()?.checkout(branch)?;
     handle_submodules(verbose)
 }
 
+/// Works through `branch_cache` in order, fixing each branch's upstream
+/// in turn. Before each attempt the plan is persisted to `.git/git_ext/`
+/// so a cherry-pick conflict can be resumed with `Continue` instead of
+/// leaving the repo on some intermediate branch with no record of what
+/// was in progress.
+fn run_restack_plan(
+    terminal: &str,
+    push: bool,
+    verbose: bool,
+    branch_cache: &mut Vec<String>,
+    original_branch: &str,
+) -> GEResult<()> {
+    while let Some(branch) = branch_cache.first().cloned() {
+        checkout(&branch, true)?;
+        let upstream = get_upstream()?;
+        state::RestackState {
+            terminal: terminal.to_string(),
+            push,
+            original_branch: original_branch.to_string(),
+            in_flight_commit: Git2Repository::discover()?.last_hash()?,
+            branch_cache: branch_cache.clone(),
+        }
+        .save()?;
+        fix_upstream(&upstream, verbose)?;
+        if push {
+            push_origin(false)?;
+        }
+        branch_cache.remove(0);
+    }
+    state::RestackState::clear()
+}
+
 fn rec_fix_up(
     terminal: &str,
     push: bool,
     verbose: bool,
     branch_cache: &mut Vec<String>,
+    original_branch: &str,
 ) -> GEResult<()> {
-    let curr_branch = get_curr_branch(verbose)?;
+    let curr_branch = get_curr_branch()?;
     if curr_branch == terminal {
-        for branch in branch_cache {
-            checkout(branch, true)?;
-            fix_upstream(&get_upstream(false)?, verbose)?;
-            if push {
-                push_origin(false)?;
-            }
-        }
-        return Ok(());
+        return run_restack_plan(terminal, push, verbose, branch_cache, original_branch);
     }
-    let curr_upstream = get_upstream(verbose)?;
+    let curr_upstream = get_upstream()?;
     checkout(&curr_upstream, false)?;
     branch_cache.insert(0, curr_branch);
-    rec_fix_up(terminal, push, verbose, branch_cache)
+    rec_fix_up(terminal, push, verbose, branch_cache, original_branch)
 }
 
 fn commit_branch(branch_name: &str, verbose: bool) -> GEResult<()> {
     run_git(vec!["branch", branch_name], true)?;
     ensure_clean()?;
     run_git(vec!["reset", "--hard", "HEAD~1"], true)?;
-    let parent_branch = get_curr_branch(verbose)?;
+    let parent_branch = get_curr_branch()?;
     run_git(vec!["checkout", branch_name], true)?;
     run_git(vec!["branch", "--set-upstream-to", &parent_branch], true)?;
-    handle_submodules(true)
+    handle_submodules(verbose)
 }
 
 fn push_origin(verbose: bool) -> GEResult<()> {
-    let branch = get_curr_branch(verbose)?;
+    let branch = get_curr_branch()?;
+    let _ = verbose;
     run_git(vec!["push", "-f", "origin", &branch], true)?;
     Ok(())
 }
 
-#[derive(Clone, Copy, Debug)]
-struct Status {
-    ahead: Option<i32>,
-    behind: Option<i32>,
-}
-
-impl Status {
-    fn parse(s: &str) -> Option<Status> {
-        let parser = Regex::new(r"(?:ahead (\d+))?(?:, )?(?:behind (\d+))?").unwrap();
-        if let Some(caps) = parser.captures(s) {
-            Some(Status {
-                ahead: caps.get(1).and_then(|it| it.as_str().parse().ok()),
-                behind: caps.get(2).and_then(|it| it.as_str().parse().ok()),
-            })
-        } else {
-            None
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-struct BranchDescriptor {
-    current: bool,
-    name: String,
-    sha: String,
-    upstream: Option<String>,
-    message: String,
-    status: Option<Status>,
-}
-
-#[derive(Clone, Debug)]
-struct BranchT {
-    desc: BranchDescriptor,
-    downstream: Vec<String>,
-}
-
-impl BranchT {
-    fn has_upstream(&self) -> bool {
-        self.desc.upstream.is_some()
-    }
-}
-
-fn branch_depth(branches_by_name: &HashMap<String, BranchT>, branch_name: &str) -> i32 {
-    if let Some(br) = branches_by_name.get(branch_name) {
-        if let Some(up) = &br.desc.upstream {
-            1 + branch_depth(branches_by_name, up)
-        } else {
-            0
-        }
-    } else {
-        0
-    }
-}
-
-fn parse_error(branch_entry: &str, reason: &str) -> Error {
-    Error::msg(format!(
-        "Unexpectedly unable to parse branch line {} ({})",
-        branch_entry, reason
-    ))
-}
-
-fn parse_branch_entry(branch_entry: &str) -> GEResult<BranchDescriptor> {
-    let whitespace = Regex::new(r"\s+")?;
-    let parts: Vec<&str> = whitespace
-        .splitn(branch_entry.trim().trim_start_matches('*').trim(), 3)
-        .collect();
-    if parts.len() != 3 {
-        return Err(parse_error(branch_entry, "wrong number of parts"));
-    }
-    let rest = parts[2];
-    let rest_expr = Regex::new(r"(?:\[([^\]]*)\] )?(.*)")?;
-    let group = rest_expr
-        .captures(rest)
-        .ok_or_else(|| parse_error(branch_entry, "failed to capture"))?;
-
-    let upstream_and_maybe_status: Option<Vec<&str>> =
-        group.get(1).map(|s| s.as_str().split(": ").collect());
-
-    let upstream = upstream_and_maybe_status
-        .clone()
-        .map(|v| String::from(v[0]));
-
-    let status = upstream_and_maybe_status
-        .and_then(|v| v.get(1).cloned())
-        .and_then(|it| Status::parse(it));
-
-    let descriptor = BranchDescriptor {
-        current: branch_entry.chars().next().unwrap_or(' ') == '*',
-        name: String::from(parts[0]),
-        sha: String::from(parts[1]),
-        message: String::from(
-            group
-                .get(2)
-                .ok_or_else(|| parse_error(branch_entry, "no message"))?
-                .as_str(),
-        ),
-        upstream,
-        status,
-    };
-
-    Ok(descriptor)
-}
-
-const INDENT_AMOUNT: i32 = 2;
-
-fn prefix_for_depth(depth: i32) -> String {
-    if depth <= 0 {
-        String::from("")
-    } else {
-        " ".repeat((INDENT_AMOUNT * depth) as usize) + "+-- "
-    }
-}
-
-fn format_tree_rooted_at(
-    branches_by_name: &HashMap<String, BranchT>,
-    root: &BranchT,
-) -> GEResult<Vec<Vec<Cell>>> {
-    let depth = branch_depth(branches_by_name, &root.desc.name);
-    let prefix = prefix_for_depth(depth) + if root.desc.current { "* " } else { "" };
-    let upstream_prefix = prefix_for_depth(depth - 1);
-
-    let mut output_rows = if let Some(up) = &root.desc.upstream {
-        if up.contains("origin") {
-            vec![vec![
-                Cell::new(upstream_prefix + up).fg(comfy_table::Color::DarkBlue),
-                Cell::new(""),
-                Cell::new(""),
-                Cell::new(""),
-                Cell::new(""),
-            ]]
-        } else if !branches_by_name.contains_key(up) {
-            vec![vec![
-                Cell::new(upstream_prefix + up + " [missing]").fg(comfy_table::Color::Red),
-                Cell::new(""),
-                Cell::new(""),
-                Cell::new(""),
-                Cell::new(""),
-            ]]
-        } else {
-            vec![]
-        }
-    } else {
-        vec![]
-    };
-    output_rows.push(vec![
-        Cell::new(prefix + &root.desc.name),
-        Cell::new(root.desc.sha.clone()),
-        Cell::new(
-            root.desc
-                .status
-                .and_then(|it| it.ahead)
-                .map(|it| format!("+{it}"))
-                .unwrap_or("".to_string()),
-        )
-        .fg(comfy_table::Color::DarkGreen),
-        Cell::new(
-            root.desc
-                .status
-                .and_then(|it| it.behind)
-                .map(|it| format!("-{it}"))
-                .unwrap_or("".to_string()),
-        )
-        .fg(comfy_table::Color::Red),
-        if root.desc.current {
-            Cell::new(root.desc.message.clone()).fg(comfy_table::Color::DarkGreen)
-        } else {
-            Cell::new(root.desc.message.clone())
-        },
-    ]);
-    for down_name in &root.downstream {
-        if let Some(down) = branches_by_name.get(down_name) {
-            output_rows.append(&mut format_tree_rooted_at(branches_by_name, down)?)
-        }
-    }
-    Ok(output_rows)
-}
-
-fn print_branch_tree() -> GEResult<()> {
-    let branch_names: Vec<String> = run_git(vec!["branch", "-vv"], false)?
-        .lines()
-        .map(String::from)
-        .collect();
-    let mut branch_downstream_map: HashMap<String, Vec<String>> = HashMap::new();
-    let mut branches: Vec<BranchT> = vec![];
-    for branch in &branch_names {
-        let desc = parse_branch_entry(branch)?;
-        branches.push(BranchT {
-            desc,
-            downstream: vec![],
-        });
-    }
-
-    for branch in &branches {
-        if let Some(upstream) = &branch.desc.upstream {
-            if !branch_downstream_map.contains_key(upstream) {
-                branch_downstream_map.insert(upstream.clone(), vec![]);
-            }
-            branch_downstream_map
-                .get_mut(upstream)
-                .ok_or_else(|| Error::msg("Upstream branch missing!"))?
-                .push(branch.desc.name.clone());
-        }
-    }
-
-    for branch in branches.iter_mut() {
-        if let Some(downstream) = branch_downstream_map.get(&branch.desc.name) {
-            branch.downstream = downstream.to_vec();
-        }
-    }
-
-    let mut branches_by_name: HashMap<String, BranchT> = HashMap::new();
-    for branch in &branches {
-        branches_by_name.insert(branch.desc.name.clone(), branch.clone());
-    }
-
-    let mut root_branches: Vec<BranchT> = branches
-        .into_iter()
-        .filter(|b| {
-            !b.has_upstream()
-                || !branches_by_name
-                    .contains_key(b.desc.upstream.as_ref().unwrap_or(&String::from("")))
-        })
-        .collect();
-    root_branches.sort_by_key(|br| br.desc.name.clone());
-
-    let mut all_rows: Vec<Vec<Cell>> = vec![];
-
-    for br in root_branches {
-        all_rows.append(&mut format_tree_rooted_at(&branches_by_name, &br)?)
-    }
-
-    let mut table = Table::new();
-    table.load_preset(presets::NOTHING);
-    for row in all_rows {
-        table.add_row(row);
-    }
-    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
-    table
-        .get_column_mut(0)
-        .unwrap()
-        .set_cell_alignment(CellAlignment::Left);
-    let col1 = table.get_column_mut(1).unwrap();
-    col1.set_cell_alignment(CellAlignment::Right);
-    col1.set_padding((0, 0));
-    table
-        .get_column_mut(2)
-        .unwrap()
-        .set_cell_alignment(CellAlignment::Right);
-    let col3 = table.get_column_mut(3).unwrap();
-    col3.set_cell_alignment(CellAlignment::Right);
-    col3.set_padding((0, 0));
-    table
-        .get_column_mut(4)
-        .unwrap()
-        .set_cell_alignment(CellAlignment::Left);
-    println!("{table}");
-
-    Ok(())
-}
-
 fn delete_branch(branch: &str, verbose: bool) -> GEResult<()> {
     run_git(vec!["branch", "-D", branch], verbose)?;
     Ok(())
@@ -435,7 +178,7 @@ fn add_amend_push_origin(verbose: bool) -> GEResult<()> {
 }
 
 fn rebase_onto_latest(branch: &str, verbose: bool) -> GEResult<()> {
-    let curr = get_curr_branch(false)?;
+    let curr = get_curr_branch()?;
     run_git(vec!["checkout", branch], true)?;
     run_git(vec!["pull", "--ff-only"], true)?;
     run_git(vec!["checkout", &curr], true)?;
@@ -443,10 +186,11 @@ fn rebase_onto_latest(branch: &str, verbose: bool) -> GEResult<()> {
 }
 
 fn reset_hard_origin(verbose: bool) -> GEResult<()> {
-    let curr = get_curr_branch(verbose)?;
+    let curr = get_curr_branch()?;
     ensure_clean()?;
     run_git(vec!["fetch", "origin"], true)?;
     run_git(vec!["reset", "--hard", &format!("origin/{curr}")], true)?;
+    let _ = verbose;
     Ok(())
 }
 
@@ -481,7 +225,15 @@ pub enum SubCommand {
 
     /// (alias: tree) show the tree of all branches and their upstream relations
     #[clap(alias = "tree")]
-    ShowTree {},
+    ShowTree {
+        /// order roots and siblings by name (default) or by most-recent commit first
+        #[clap(long, value_enum, default_value = "name")]
+        sort: tree::SortOrder,
+
+        /// branches whose last commit is older than this many days are highlighted as stale
+        #[clap(long, default_value_t = 30)]
+        stale_after: i64,
+    },
 
     /// (alias: po) force push to the same-named branch on the origin
     #[clap(alias = "po")]
@@ -505,6 +257,26 @@ pub enum SubCommand {
     /// (alias: rho) reset --hard to the same-named branch on the origin
     #[clap(alias = "rho")]
     ResetHardOrgin {},
+
+    /// (alias: ra) rebase the whole branch forest (every tracked branch, not just a single chain)
+    #[clap(alias = "ra")]
+    RestackAll {
+        #[clap(long)]
+        push: bool,
+    },
+
+    /// resume a RecFixUp that stopped on a cherry-pick conflict, once it's resolved and staged
+    Continue {},
+
+    /// abandon an in-progress RecFixUp, discarding the conflicted cherry-pick
+    Abort {},
+
+    /// find the commit that was the tip of the current branch at a given date/time
+    FindAt {
+        when: String,
+        #[clap(long)]
+        checkout: bool,
+    },
 }
 
 #[derive(Debug, Parser)]
@@ -520,24 +292,31 @@ fn main() {
     use SubCommand::*;
     let verbose = opt.verbose;
     let result = match opt.cmd {
-        Lasthash {} => lasthash(verbose).map(|res| {
-            println!("{}", res);
-        }),
-        ShowUp {} => get_upstream(verbose).map(|res| {
+        Lasthash {} => Git2Repository::discover()
+            .and_then(|repo| repo.last_hash())
+            .map(|res| {
+                println!("{}", res);
+            }),
+        ShowUp {} => get_upstream().map(|res| {
             println!("{}", res);
         }),
-        FixUp {} => fix_upstream(&get_upstream(verbose).unwrap(), verbose),
+        FixUp {} => fix_upstream(&get_upstream().unwrap(), verbose),
         Up { branch } => fix_upstream(&branch, verbose),
-        RecFixUp { terminal, push } => rec_fix_up(&terminal, push, verbose, &mut vec![]),
+        RecFixUp { terminal, push } => get_curr_branch()
+            .and_then(|original| rec_fix_up(&terminal, push, verbose, &mut vec![], &original)),
         CommitBr { name } => commit_branch(&name, verbose),
         PushOrigin {} => push_origin(verbose),
-        ShowTree {} => print_branch_tree(),
+        ShowTree { sort, stale_after } => tree::print_branch_tree(sort, stale_after),
         Purge { prefix, no_confirm } => purge(&prefix, no_confirm, verbose),
         AddAmendPushOrigin {} => add_amend_push_origin(verbose),
         RebaseOntoLatest { branch } => {
             rebase_onto_latest(&branch.unwrap_or("main".to_string()), verbose)
         }
         ResetHardOrgin {} => reset_hard_origin(verbose),
+        RestackAll { push } => restack::restack_all(push, verbose),
+        Continue {} => resume::continue_restack(verbose),
+        Abort {} => resume::abort_restack(),
+        FindAt { when, checkout } => findat::find_at(&when, checkout, verbose),
     };
     if result.is_err() {
         eprintln!("{}", result.unwrap_err());
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use anyhow::Error;
+
+use crate::git::{run_git, Git2Repository, GitRepository};
+use crate::{checkout, run_restack_plan, GEResult};
+
+/// The `terminal` recorded in `RestackState` for a `RestackAll` run. The
+/// field only matters for `RecFixUp`'s single-chain walk; here it's
+/// purely informational, since the whole forest (not one chain) is
+/// being restacked.
+const RESTACK_ALL_TERMINAL: &str = "(restack-all)";
+
+/// Rebases every branch in the upstream forest, not just a single chain.
+///
+/// Branches whose upstream is an `origin/...` ref are treated as
+/// immovable roots and pulled (fast-forward only) before anything
+/// downstream of them is touched. Branches with a `[missing]` upstream
+/// are skipped with a warning, and a cycle in the upstream graph aborts
+/// the whole operation rather than recursing forever.
+///
+/// Like `RecFixUp`, each branch's rebase is run through
+/// `run_restack_plan`, so a cherry-pick conflict here can be resumed or
+/// abandoned with `Continue`/`Abort` just like a single-chain restack.
+pub fn restack_all(push: bool, verbose: bool) -> GEResult<()> {
+    let repo = Git2Repository::discover()?;
+    let original_branch = repo.current_branch()?;
+    let descriptors = repo.branches()?;
+    let names: HashSet<String> = descriptors.iter().map(|d| d.name.clone()).collect();
+
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    let mut remote_tracking_roots: HashSet<String> = HashSet::new();
+    let mut roots: Vec<String> = vec![];
+
+    for desc in &descriptors {
+        match &desc.upstream {
+            None => roots.push(desc.name.clone()),
+            Some(up) if up.contains("origin") => {
+                remote_tracking_roots.insert(desc.name.clone());
+                roots.push(desc.name.clone());
+            }
+            Some(up) if !names.contains(up) => {
+                println!(
+                    "Warning: branch '{}' has a missing upstream '{}'; skipping",
+                    desc.name, up
+                );
+                // Left in place as a root, so its own downstream branches
+                // are still reached instead of looking like a cycle.
+                roots.push(desc.name.clone());
+            }
+            Some(up) => {
+                children
+                    .entry(up.clone())
+                    .or_default()
+                    .push(desc.name.clone());
+            }
+        }
+    }
+
+    let order = topological_order(&roots, &children)?;
+    let root_set: HashSet<String> = roots.iter().cloned().collect();
+
+    for branch in &order {
+        if remote_tracking_roots.contains(branch) {
+            checkout(branch, true)?;
+            run_git(vec!["pull", "--ff-only"], true)?;
+        }
+    }
+
+    let mut to_restack: Vec<String> = order
+        .into_iter()
+        .filter(|b| !root_set.contains(b))
+        .collect();
+    if !to_restack.is_empty() {
+        run_restack_plan(
+            RESTACK_ALL_TERMINAL,
+            push,
+            verbose,
+            &mut to_restack,
+            &original_branch,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Topologically orders `roots` and everything reachable from them
+/// through `children`, emitting every branch only once its upstream has
+/// already been emitted. Any branch left unreached is part of a cycle.
+fn topological_order(
+    roots: &[String],
+    children: &HashMap<String, Vec<String>>,
+) -> GEResult<Vec<String>> {
+    let mut order = roots.to_vec();
+    let mut emitted: HashSet<String> = roots.iter().cloned().collect();
+    let mut queue: VecDeque<String> = roots.iter().cloned().collect();
+
+    while let Some(parent) = queue.pop_front() {
+        if let Some(kids) = children.get(&parent) {
+            for kid in kids {
+                if emitted.insert(kid.clone()) {
+                    order.push(kid.clone());
+                    queue.push_back(kid.clone());
+                }
+            }
+        }
+    }
+
+    for key in children.keys() {
+        if !emitted.contains(key) {
+            return Err(Error::msg(format!(
+                "cycle detected in branch upstream graph (involving '{}'); aborting restack",
+                key
+            )));
+        }
+    }
+
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::topological_order;
+
+    fn children_of(pairs: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        pairs
+            .iter()
+            .map(|(parent, kids)| {
+                (
+                    parent.to_string(),
+                    kids.iter().map(|k| k.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_root_linear_chain() {
+        let children = children_of(&[("main", &["feature"]), ("feature", &["feature-2"])]);
+        let order = topological_order(&[String::from("main")], &children).unwrap();
+        assert_eq!(order, vec!["main", "feature", "feature-2"]);
+    }
+
+    #[test]
+    fn diverging_stack_from_one_root() {
+        let children = children_of(&[("main", &["feature-a", "feature-b"])]);
+        let order = topological_order(&[String::from("main")], &children).unwrap();
+        assert_eq!(order[0], "main");
+        assert_eq!(
+            order[1..].iter().collect::<std::collections::HashSet<_>>(),
+            vec![&String::from("feature-a"), &String::from("feature-b")]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn multiple_independent_roots() {
+        let children = children_of(&[("main", &["feature"]), ("develop", &["hotfix"])]);
+        let roots = vec![String::from("main"), String::from("develop")];
+        let order = topological_order(&roots, &children).unwrap();
+        assert_eq!(order.len(), 4);
+        let pos = |name: &str| order.iter().position(|b| b == name).unwrap();
+        assert!(pos("main") < pos("feature"));
+        assert!(pos("develop") < pos("hotfix"));
+    }
+
+    #[test]
+    fn cycle_is_rejected() {
+        let children = children_of(&[("a", &["b"]), ("b", &["a"])]);
+        let err = topological_order(&[], &children).unwrap_err();
+        assert!(err.to_string().contains("cycle detected"));
+    }
+
+    #[test]
+    fn empty_graph_yields_empty_order() {
+        let order = topological_order(&[], &HashMap::new()).unwrap();
+        assert!(order.is_empty());
+    }
+}
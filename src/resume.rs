@@ -0,0 +1,55 @@
+use anyhow::Error;
+
+use crate::git::{Git2Repository, GitRepository};
+use crate::state::RestackState;
+use crate::{checkout, handle_submodules, push_origin, run_restack_plan, GEResult};
+
+/// Resumes a `RecFixUp` that stopped on a cherry-pick conflict: the
+/// conflict must already be resolved and staged. Finishes that
+/// cherry-pick, then carries on with whatever branches were left in the
+/// saved plan.
+pub fn continue_restack(verbose: bool) -> GEResult<()> {
+    let state = RestackState::load()?
+        .ok_or_else(|| Error::msg("no restack is in progress (no state file found)"))?;
+    let repo = Git2Repository::discover()?;
+    if repo.has_conflicts()? {
+        return Err(Error::msg(
+            "there are still unresolved conflicts; resolve and stage them before continuing",
+        ));
+    }
+    let in_progress = repo.cherry_pick_head()?;
+    if in_progress != state.in_flight_commit {
+        return Err(Error::msg(format!(
+            "the in-progress cherry-pick ({in_progress}) doesn't match the saved restack plan \
+             (expected {}); resolve manually or run `git_ext abort`",
+            state.in_flight_commit
+        )));
+    }
+    repo.finish_cherry_pick()?;
+    handle_submodules(verbose)?;
+    if state.push {
+        push_origin(false)?;
+    }
+    let mut branch_cache = state.branch_cache.clone();
+    if !branch_cache.is_empty() {
+        branch_cache.remove(0);
+    }
+    run_restack_plan(
+        &state.terminal,
+        state.push,
+        verbose,
+        &mut branch_cache,
+        &state.original_branch,
+    )
+}
+
+/// Abandons an in-progress `RecFixUp`, discarding the conflicted
+/// cherry-pick and returning to the branch the operation started from.
+pub fn abort_restack() -> GEResult<()> {
+    let state = RestackState::load()?
+        .ok_or_else(|| Error::msg("no restack is in progress (no state file found)"))?;
+    let repo = Git2Repository::discover()?;
+    repo.abort_cherry_pick()?;
+    checkout(&state.original_branch, true)?;
+    RestackState::clear()
+}
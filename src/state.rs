@@ -0,0 +1,79 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Error;
+
+use crate::GEResult;
+
+const STATE_DIR: &str = "git_ext";
+const STATE_FILE: &str = "restack.state";
+
+/// The in-flight plan for a `RecFixUp` run, persisted to `.git/git_ext/`
+/// so a cherry-pick conflict can be resumed (or abandoned) instead of
+/// leaving the repo on some intermediate branch with no record of what
+/// was in progress.
+#[derive(Clone, Debug)]
+pub struct RestackState {
+    pub terminal: String,
+    pub push: bool,
+    pub original_branch: String,
+    pub in_flight_commit: String,
+    pub branch_cache: Vec<String>,
+}
+
+fn state_path() -> GEResult<PathBuf> {
+    let repo = git2::Repository::discover(".")?;
+    let dir = repo.path().join(STATE_DIR);
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(STATE_FILE))
+}
+
+impl RestackState {
+    pub fn save(&self) -> GEResult<()> {
+        let mut lines = vec![
+            self.terminal.clone(),
+            if self.push { "1" } else { "0" }.to_string(),
+            self.original_branch.clone(),
+            self.in_flight_commit.clone(),
+        ];
+        lines.extend(self.branch_cache.iter().cloned());
+        fs::write(state_path()?, lines.join("\n"))?;
+        Ok(())
+    }
+
+    pub fn load() -> GEResult<Option<RestackState>> {
+        let path = state_path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)?;
+        let mut lines = contents.lines();
+        let missing = |field: &str| Error::msg(format!("corrupt restack state: missing {field}"));
+        let terminal = lines.next().ok_or_else(|| missing("terminal"))?.to_string();
+        let push = lines.next().ok_or_else(|| missing("push flag"))? == "1";
+        let original_branch = lines
+            .next()
+            .ok_or_else(|| missing("original branch"))?
+            .to_string();
+        let in_flight_commit = lines
+            .next()
+            .ok_or_else(|| missing("in-flight commit"))?
+            .to_string();
+        let branch_cache = lines.map(String::from).collect();
+        Ok(Some(RestackState {
+            terminal,
+            push,
+            original_branch,
+            in_flight_commit,
+            branch_cache,
+        }))
+    }
+
+    pub fn clear() -> GEResult<()> {
+        let path = state_path()?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use clap::ValueEnum;
+use comfy_table::{presets, Cell, CellAlignment, Table};
+
+use crate::git::{BranchDescriptor, Git2Repository, GitRepository};
+use crate::GEResult;
+
+/// How to order root branches, and the siblings under each branch, when
+/// printing the tree.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum SortOrder {
+    /// Alphabetically by branch name (the historical default).
+    Name,
+    /// Newest commit first, to surface stale stacks at the bottom.
+    Recency,
+}
+
+#[derive(Clone, Debug)]
+struct BranchT {
+    desc: BranchDescriptor,
+    downstream: Vec<String>,
+}
+
+impl BranchT {
+    fn has_upstream(&self) -> bool {
+        self.desc.upstream.is_some()
+    }
+}
+
+fn branch_depth(branches_by_name: &HashMap<String, BranchT>, branch_name: &str) -> i32 {
+    if let Some(br) = branches_by_name.get(branch_name) {
+        if let Some(up) = &br.desc.upstream {
+            1 + branch_depth(branches_by_name, up)
+        } else {
+            0
+        }
+    } else {
+        0
+    }
+}
+
+const INDENT_AMOUNT: i32 = 2;
+
+fn prefix_for_depth(depth: i32) -> String {
+    if depth <= 0 {
+        String::from("")
+    } else {
+        " ".repeat((INDENT_AMOUNT * depth) as usize) + "+-- "
+    }
+}
+
+fn sort_branch_names(
+    names: &mut [String],
+    branches_by_name: &HashMap<String, BranchT>,
+    sort: SortOrder,
+) {
+    match sort {
+        SortOrder::Name => names.sort_by_key(|name| name.clone()),
+        SortOrder::Recency => names.sort_by_key(|name| {
+            branches_by_name
+                .get(name)
+                .map(|br| -br.desc.timestamp)
+                .unwrap_or(0)
+        }),
+    }
+}
+
+const MINUTE: i64 = 60;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+const WEEK: i64 = 7 * DAY;
+const MONTH: i64 = 30 * DAY;
+const YEAR: i64 = 365 * DAY;
+
+/// Renders a commit timestamp relative to `now` as a short age like
+/// "3d" or "2w", the way other git tooling abbreviates recency.
+fn format_age(now: i64, timestamp: i64) -> String {
+    let diff = (now - timestamp).max(0);
+    if diff < MINUTE {
+        "now".to_string()
+    } else if diff < HOUR {
+        format!("{}m", diff / MINUTE)
+    } else if diff < DAY {
+        format!("{}h", diff / HOUR)
+    } else if diff < WEEK {
+        format!("{}d", diff / DAY)
+    } else if diff < MONTH {
+        format!("{}w", diff / WEEK)
+    } else if diff < YEAR {
+        format!("{}mo", diff / MONTH)
+    } else {
+        format!("{}y", diff / YEAR)
+    }
+}
+
+fn format_tree_rooted_at(
+    branches_by_name: &HashMap<String, BranchT>,
+    root: &BranchT,
+    now: i64,
+    stale_after_days: i64,
+    sort: SortOrder,
+) -> GEResult<Vec<Vec<Cell>>> {
+    let depth = branch_depth(branches_by_name, &root.desc.name);
+    let prefix = prefix_for_depth(depth) + if root.desc.current { "* " } else { "" };
+    let upstream_prefix = prefix_for_depth(depth - 1);
+
+    let mut output_rows = if let Some(up) = &root.desc.upstream {
+        if up.contains("origin") {
+            vec![vec![
+                Cell::new(upstream_prefix + up).fg(comfy_table::Color::DarkBlue),
+                Cell::new(""),
+                Cell::new(""),
+                Cell::new(""),
+                Cell::new(""),
+                Cell::new(""),
+            ]]
+        } else if !branches_by_name.contains_key(up) {
+            vec![vec![
+                Cell::new(upstream_prefix + up + " [missing]").fg(comfy_table::Color::Red),
+                Cell::new(""),
+                Cell::new(""),
+                Cell::new(""),
+                Cell::new(""),
+                Cell::new(""),
+            ]]
+        } else {
+            vec![]
+        }
+    } else {
+        vec![]
+    };
+
+    let is_stale = now - root.desc.timestamp >= stale_after_days * DAY;
+    let age_cell = Cell::new(format_age(now, root.desc.timestamp)).fg(if is_stale {
+        comfy_table::Color::Red
+    } else {
+        comfy_table::Color::DarkGrey
+    });
+
+    output_rows.push(vec![
+        Cell::new(prefix + &root.desc.name),
+        Cell::new(root.desc.sha.clone()),
+        Cell::new(
+            root.desc
+                .status
+                .and_then(|it| it.ahead)
+                .map(|it| format!("+{it}"))
+                .unwrap_or("".to_string()),
+        )
+        .fg(comfy_table::Color::DarkGreen),
+        Cell::new(
+            root.desc
+                .status
+                .and_then(|it| it.behind)
+                .map(|it| format!("-{it}"))
+                .unwrap_or("".to_string()),
+        )
+        .fg(comfy_table::Color::Red),
+        age_cell,
+        if root.desc.current {
+            Cell::new(root.desc.message.clone()).fg(comfy_table::Color::DarkGreen)
+        } else {
+            Cell::new(root.desc.message.clone())
+        },
+    ]);
+
+    let mut downstream = root.downstream.clone();
+    sort_branch_names(&mut downstream, branches_by_name, sort);
+    for down_name in &downstream {
+        if let Some(down) = branches_by_name.get(down_name) {
+            output_rows.append(&mut format_tree_rooted_at(
+                branches_by_name,
+                down,
+                now,
+                stale_after_days,
+                sort,
+            )?)
+        }
+    }
+    Ok(output_rows)
+}
+
+pub fn print_branch_tree(sort: SortOrder, stale_after_days: i64) -> GEResult<()> {
+    let repo = Git2Repository::discover()?;
+    let mut descriptors = repo.branches()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+    // `git status --branch` is the only thing that can tell us the
+    // currently checked-out branch's ahead/behind counts without
+    // trusting an upstream remote-tracking ref to be up to date, so use
+    // it for that one row in preference to `graph_ahead_behind`.
+    if let Ok(current) = crate::git::current_status() {
+        if let Some(desc) = descriptors.iter_mut().find(|d| d.current) {
+            desc.status = Some(current.status);
+        }
+    }
+
+    let mut branch_downstream_map: HashMap<String, Vec<String>> = HashMap::new();
+    let mut branches: Vec<BranchT> = descriptors
+        .into_iter()
+        .map(|desc| BranchT {
+            desc,
+            downstream: vec![],
+        })
+        .collect();
+
+    for branch in &branches {
+        if let Some(upstream) = &branch.desc.upstream {
+            branch_downstream_map
+                .entry(upstream.clone())
+                .or_default()
+                .push(branch.desc.name.clone());
+        }
+    }
+
+    for branch in branches.iter_mut() {
+        if let Some(downstream) = branch_downstream_map.get(&branch.desc.name) {
+            branch.downstream = downstream.to_vec();
+        }
+    }
+
+    let mut branches_by_name: HashMap<String, BranchT> = HashMap::new();
+    for branch in &branches {
+        branches_by_name.insert(branch.desc.name.clone(), branch.clone());
+    }
+
+    let mut root_branches: Vec<BranchT> = branches
+        .into_iter()
+        .filter(|b| {
+            !b.has_upstream()
+                || !branches_by_name
+                    .contains_key(b.desc.upstream.as_ref().unwrap_or(&String::from("")))
+        })
+        .collect();
+    match sort {
+        SortOrder::Name => root_branches.sort_by_key(|br| br.desc.name.clone()),
+        SortOrder::Recency => root_branches.sort_by_key(|br| -br.desc.timestamp),
+    }
+
+    let mut all_rows: Vec<Vec<Cell>> = vec![];
+
+    for br in root_branches {
+        all_rows.append(&mut format_tree_rooted_at(
+            &branches_by_name,
+            &br,
+            now,
+            stale_after_days,
+            sort,
+        )?)
+    }
+
+    let mut table = Table::new();
+    table.load_preset(presets::NOTHING);
+    for row in all_rows {
+        table.add_row(row);
+    }
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+    table
+        .get_column_mut(0)
+        .ok_or_else(|| Error::msg("branch tree table is missing its name column"))?
+        .set_cell_alignment(CellAlignment::Left);
+    let col1 = table
+        .get_column_mut(1)
+        .ok_or_else(|| Error::msg("branch tree table is missing its sha column"))?;
+    col1.set_cell_alignment(CellAlignment::Right);
+    col1.set_padding((0, 0));
+    table
+        .get_column_mut(2)
+        .ok_or_else(|| Error::msg("branch tree table is missing its ahead column"))?
+        .set_cell_alignment(CellAlignment::Right);
+    let col3 = table
+        .get_column_mut(3)
+        .ok_or_else(|| Error::msg("branch tree table is missing its behind column"))?;
+    col3.set_cell_alignment(CellAlignment::Right);
+    col3.set_padding((0, 0));
+    table
+        .get_column_mut(4)
+        .ok_or_else(|| Error::msg("branch tree table is missing its age column"))?
+        .set_cell_alignment(CellAlignment::Right);
+    table
+        .get_column_mut(5)
+        .ok_or_else(|| Error::msg("branch tree table is missing its message column"))?
+        .set_cell_alignment(CellAlignment::Left);
+    println!("{table}");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_age, DAY, HOUR, MINUTE, MONTH, WEEK, YEAR};
+
+    #[test]
+    fn just_now_rounds_to_now() {
+        assert_eq!(format_age(1_000, 1_000 - MINUTE + 1), "now");
+    }
+
+    #[test]
+    fn minutes_and_hours() {
+        assert_eq!(format_age(1_000_000, 1_000_000 - 5 * MINUTE), "5m");
+        assert_eq!(format_age(1_000_000, 1_000_000 - 5 * HOUR), "5h");
+    }
+
+    #[test]
+    fn days_weeks_and_months() {
+        assert_eq!(format_age(1_000_000, 1_000_000 - 3 * DAY), "3d");
+        assert_eq!(format_age(1_000_000, 1_000_000 - 2 * WEEK), "2w");
+        assert_eq!(format_age(1_000_000, 1_000_000 - 2 * MONTH), "2mo");
+    }
+
+    #[test]
+    fn crosses_a_year_boundary() {
+        assert_eq!(format_age(1_000_000, 1_000_000 - 2 * YEAR), "2y");
+    }
+
+    #[test]
+    fn unit_boundaries_round_down_into_the_next_unit() {
+        assert_eq!(format_age(DAY, HOUR), "23h");
+        assert_eq!(format_age(DAY, 0), "1d");
+    }
+
+    #[test]
+    fn future_timestamp_clamps_to_now() {
+        assert_eq!(format_age(1_000, 1_000 + 500), "now");
+    }
+}